@@ -1,8 +1,7 @@
-use rand;
-
 use tetra::{self, State, Context, ContextBuilder};
 use tetra::audio;
-use tetra::graphics::{self, Color, DrawParams, Font, Texture, Rectangle};
+use tetra::graphics::{self, Color, DrawParams, Font, Texture};
+use tetra::graphics::mesh::{GeometryBuilder, ShapeStyle};
 use tetra::math::Vec2;
 use tetra::input::{self,Key};
 use std::result;
@@ -16,15 +15,108 @@ const PLAYER_TURN_RATE: f32 = 3.0;
 // Seconds between shots
 const PLAYER_SHOT_TIME: f32 = 0.5;
 
-const PLAYER_LIFE: f32 = 1.0;
+// Number of rock hits that end the game; the last one is game over.
+const PLAYER_LIVES: i32 = 3;
+// Seconds of blinking invulnerability after a hit.
+const PLAYER_INVULN_TIME: f32 = 2.0;
 const SHOT_LIFE: f32 = 2.0;
 const ROCK_LIFE: f32 = 1.0;
 
 const MAX_ROCK_VEL: f32 = 50.0;
 
+/// Rock size tier. Killing a rock above `Small` splits it into two
+/// one-tier-smaller rocks instead of just removing it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RockSize {
+    Large,
+    Medium,
+    Small,
+}
+
+impl RockSize {
+    // Multiplies bounding radius and draw scale.
+    fn scale(self) -> f32 {
+        match self {
+            RockSize::Large => 1.0,
+            RockSize::Medium => 0.5,
+            RockSize::Small => 0.25,
+        }
+    }
+
+    fn smaller(self) -> Option<RockSize> {
+        match self {
+            RockSize::Large => Some(RockSize::Medium),
+            RockSize::Medium => Some(RockSize::Small),
+            RockSize::Small => None,
+        }
+    }
+
+    // Smaller rocks are worth more, since they're harder to land a hit on.
+    fn score_value(self) -> i32 {
+        match self {
+            RockSize::Large => 1,
+            RockSize::Medium => 2,
+            RockSize::Small => 3,
+        }
+    }
+}
+
+/// Which subsystem the player has powered up. Only one can be active at a
+/// time, so flying, shooting and scanning for off-screen rocks is a tradeoff
+/// instead of always-on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ShipSystem {
+    Engines,
+    Weapons,
+    Radar,
+}
+
 type Point2 = Vec2<f32>;
 type Vector2 = Vec2<f32>;
 
+/// Seed used when `ASTROBLASTO_SEED` isn't set, so a bare run is still reproducible.
+const DEFAULT_RNG_SEED: u64 = 0xA5EE_D5EE_D5EE_D5ED;
+
+/// Minimal PCG32 generator, threaded explicitly instead of calling `rand::random()`
+/// from wherever, so rock spawns (and eventually replays) are reproducible from a seed.
+#[derive(Debug)]
+struct Rand32 {
+    state: u64,
+    inc: u64,
+}
+
+impl Rand32 {
+    fn new(seed: u64) -> Self {
+        let mut rng = Rand32 { state: 0, inc: (seed << 1) | 1 };
+        rng.next_u32();
+        rng.state = rng.state.wrapping_add(seed);
+        rng.next_u32();
+        rng
+    }
+
+    fn next_u32(&mut self) -> u32 {
+        let old_state = self.state;
+        self.state = old_state
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(self.inc);
+        let xorshifted = (((old_state >> 18) ^ old_state) >> 27) as u32;
+        let rot = (old_state >> 59) as u32;
+        xorshifted.rotate_right(rot)
+    }
+
+    /// Uniform float in [0, 1).
+    fn next_f32(&mut self) -> f32 {
+        (self.next_u32() >> 8) as f32 / 16_777_216.0
+    }
+}
+
+fn rng_seed_from_env() -> u64 {
+    std::env::var("ASTROBLASTO_SEED")
+        .ok()
+        .and_then(|s| s.parse::<u64>().ok())
+        .unwrap_or(DEFAULT_RNG_SEED)
+}
+
 // normalized
 fn vec_from_angle(angle: f32) -> Vector2 {
     let vx = angle.sin();
@@ -32,10 +124,10 @@ fn vec_from_angle(angle: f32) -> Vector2 {
     Vector2::new(vx, vy)
 }
 
-// rand::random::<f32>() range (0,1)
-fn random_vec(max_magnitude: f32) -> Vector2 {
-    let angle = rand::random::<f32>() * 2.0 * std::f32::consts::PI;
-    let mag = rand::random::<f32>() * max_magnitude;
+// rng.next_f32() range [0,1)
+fn random_vec(rng: &mut Rand32, max_magnitude: f32) -> Vector2 {
+    let angle = rng.next_f32() * 2.0 * std::f32::consts::PI;
+    let mag = rng.next_f32() * max_magnitude;
     vec_from_angle(angle) * (mag)
 }
 
@@ -47,10 +139,18 @@ struct Actor {
     velocity: Vector2,
 
     // lazily overload "life" with a
-    // double meaning
-    // for  shots, it is the time left to live,
-    // for players and rocks, it is the actual hit points.
+    // triple meaning:
+    // for shots, it is the time left to live,
+    // for rocks, it is the actual hit points,
+    // for the player, it is remaining invulnerability time (<= 0 when vulnerable).
     life: f32,
+
+    // Used for circle-based collision instead of axis-aligned rectangles,
+    // since the sprites are round and rotate.
+    bounding_radius: f32,
+
+    // Only meaningful for rocks; player and shots are always `Large`.
+    size: RockSize,
 }
 
 impl Actor {
@@ -70,34 +170,43 @@ impl Actor {
 
     fn create_player(ctx: &mut Context) -> tetra::Result<Actor> {
         let player_texture = Texture::new(ctx, "./resources/player.png")?;
+        let bounding_radius = player_texture.width() as f32 / 2.0;
         Ok( Actor {
             texture: player_texture,
             pos: Vec2::zero(),
             facing: 0.0,
             velocity: Vec2::zero(),
-            life: PLAYER_LIFE,
+            life: 0.0, // not invulnerable at spawn
+            bounding_radius,
+            size: RockSize::Large,
         })
     }
-    
-    fn create_rock(ctx: &mut Context) -> tetra::Result<Actor> {
+
+    fn create_rock(ctx: &mut Context, size: RockSize) -> tetra::Result<Actor> {
         let rock_texture = Texture::new(ctx, "./resources/rock.png")?;
+        let bounding_radius = rock_texture.width() as f32 / 2.0 * size.scale();
         Ok(Actor {
             texture: rock_texture,
             pos: Vec2::zero(),
             facing: 0.0,
             velocity: Vec2::zero(),
             life: ROCK_LIFE,
+            bounding_radius,
+            size,
         })
     }
 
     fn create_shot(ctx: &mut Context) -> tetra::Result<Actor> {
         let shot_texture = Texture::new(ctx, "./resources/shot.png")?;
+        let bounding_radius = shot_texture.width() as f32 / 2.0;
         Ok( Actor {
             texture: shot_texture,
             pos: Vec2::zero(),
             facing: 0.,
             velocity: Vec2::zero(),
             life: SHOT_LIFE,
+            bounding_radius,
+            size: RockSize::Large,
         })
     }
 
@@ -153,16 +262,16 @@ impl Actor {
 
 // Params: num - num. of rocks to generate
 // min_radius, max_radius - radius range for rocks.
-fn create_rocks(ctx: &mut Context, num: i32, exclusion: Point2, min_radius: f32, max_radius: f32) -> Vec<Actor> {
+fn create_rocks(ctx: &mut Context, rng: &mut Rand32, num: i32, exclusion: Point2, min_radius: f32, max_radius: f32) -> Vec<Actor> {
     let mut new_rock = || -> tetra::Result<Actor> {
         assert!(max_radius > min_radius);
-        let mut rock : Actor = Actor::create_rock(ctx)?;
+        let mut rock : Actor = Actor::create_rock(ctx, RockSize::Large)?;
         //random angle
-        let r_angle = rand::random::<f32>() * 2.0 * std::f32::consts::PI;
-        let r_distance = rand::random::<f32>() * (max_radius - min_radius) + min_radius;
+        let r_angle = rng.next_f32() * 2.0 * std::f32::consts::PI;
+        let r_distance = rng.next_f32() * (max_radius - min_radius) + min_radius;
         // rock positioned wrt player
         rock.pos = exclusion + vec_from_angle(r_angle) * r_distance;
-        rock.velocity = random_vec(MAX_ROCK_VEL);
+        rock.velocity = random_vec(rng, MAX_ROCK_VEL);
         Ok(rock)
     };
     //let x = new_rock.ok();
@@ -210,7 +319,11 @@ impl Default for InputState {
     }
 }
 
-fn player_handle_input(actor: &mut Actor, input: &InputState, dt: f32) {
+fn player_handle_input(actor: &mut Actor, input: &InputState, dt: f32, active_system: ShipSystem) {
+    if active_system != ShipSystem::Engines {
+        return;
+    }
+
     actor.facing += dt * PLAYER_TURN_RATE * input.xaxis;
 
     if input.yaxis > 0.0 {
@@ -259,15 +372,20 @@ struct GameState {
     screen_height: f32,
     input: InputState,
     player_shot_timeout: f32,
+    rng: Rand32,
+    active_system: ShipSystem,
+    lives: i32,
 }
 
 impl GameState {
     fn new(ctx: &mut Context) -> tetra::Result<GameState> {
         print_instruction();
 
+        let mut rng = Rand32::new(rng_seed_from_env());
+
         let assets = Assets::new(ctx)?;
         let player = Actor::create_player(ctx)?;
-        let rocks = create_rocks(ctx, 5, player.pos, 100.0, 250.0);
+        let rocks = create_rocks(ctx, &mut rng, 5, player.pos, 100.0, 250.0);
 
         let s = GameState {
             player,
@@ -280,14 +398,31 @@ impl GameState {
             screen_height: tetra::window::get_height(ctx) as f32,
             input: InputState::default(),
             player_shot_timeout: 0.0,
+            rng,
+            active_system: ShipSystem::Engines,
+            lives: PLAYER_LIVES,
         };
 
         Ok(s)
     }
-    
+
+    /// Alpha to draw the player with: blinks while `player.life`
+    /// (the remaining invulnerability time) is running.
+    fn player_alpha(&self) -> f32 {
+        if self.player.life > 0.0 && (self.player.life * 10.0) as i32 % 2 == 0 {
+            0.3
+        } else {
+            1.0
+        }
+    }
+
     fn fire_player_shot(&mut self, ctx: &mut Context) -> tetra::Result{
+        if self.active_system != ShipSystem::Weapons {
+            return Ok(());
+        }
+
         self.player_shot_timeout = PLAYER_SHOT_TIME;
-    
+
         let player = &self.player;
         let mut shot = Actor::create_shot(ctx)?;
         shot.pos = player.pos;
@@ -308,46 +443,51 @@ impl GameState {
         self.rocks.retain(|r| r.life > 0.0);
     }
     
-    fn handle_collision(&mut self, ctx: &Context) {
+    fn handle_collision(&mut self, ctx: &mut Context) {
+        let mut spawned_rocks: Vec<Actor> = Vec::new();
         for rock in &mut self.rocks {
-            let bound_rock = Rectangle::new (
-                rock.pos.x,
-                rock.pos.y,
-                rock.width(),
-                rock.height(),
-            );
-            let bound_player = Rectangle::new (
-                self.player.pos.x,
-                self.player.pos.y,
-                self.player.width(),
-                self.player.height(),
-            );
-            if bound_rock.intersects(&bound_player) {
-                self.player.life = 0.0;
+            let player_hit = self.player.life <= 0.0
+                && (rock.pos - self.player.pos).magnitude() < rock.bounding_radius + self.player.bounding_radius;
+            if player_hit {
+                self.lives -= 1;
+                self.player.pos = Vec2::zero();
+                self.player.velocity = Vec2::zero();
+                self.player.facing = 0.0;
+                self.player.life = PLAYER_INVULN_TIME;
             };
             for shot in &mut self.shots {
-                let bound_shot = Rectangle::new (
-                    shot.pos.x,
-                    shot.pos.y,
-                    shot.width(),
-                    shot.height(),
-                );
-                if bound_rock.intersects(&bound_shot)
-                {
+                if rock.life <= 0.0 {
+                    break;
+                }
+                if shot.life <= 0.0 {
+                    continue;
+                }
+                if (rock.pos - shot.pos).magnitude() < rock.bounding_radius + shot.bounding_radius {
                     shot.life = 0.0;
                     rock.life = 0.0;
-                    self.score += 1;
+                    self.score += rock.size.score_value();
+
+                    if let Some(child_size) = rock.size.smaller() {
+                        for _ in 0..2 {
+                            if let Ok(mut child) = Actor::create_rock(ctx, child_size) {
+                                child.pos = rock.pos;
+                                child.velocity = rock.velocity + random_vec(&mut self.rng, MAX_ROCK_VEL);
+                                spawned_rocks.push(child);
+                            }
+                        }
+                    }
 
                     let _ = self.assets.hit_sound.play(ctx);
                 }
             }
         }
+        self.rocks.extend(spawned_rocks);
     }
 
     fn check_for_level_respawn(&mut self, ctx: &mut Context) {
         if self.rocks.is_empty() {
             self.level += 1;
-            let r = create_rocks(ctx, self.level + 5, self.player.pos, 100.0, 250.0);
+            let r = create_rocks(ctx, &mut self.rng, self.level + 5, self.player.pos, 100.0, 250.0);
             self.rocks.extend(r);
         }
     }
@@ -370,21 +510,75 @@ fn draw_actor(
     ctx: &mut Context,
     actor: &Actor,
     world_coords: (f32, f32),
+    alpha: f32,
 ) -> tetra::Result {
     let (screen_w, screen_h) = world_coords;
     let pos = world_to_screen_coords(screen_w, screen_h, actor.pos);
     let image = &actor.texture;
+    let scale = actor.size.scale();
     let drawparams = graphics::DrawParams::new()
         .position(pos)
         .rotation(actor.facing as f32)
-        .origin(Point2::new(0.5, 0.5));
+        .origin(Point2::new(0.5, 0.5))
+        .scale(Vec2::new(scale, scale))
+        .color(Color::rgba(1.0, 1.0, 1.0, alpha));
     graphics::draw(ctx, image, drawparams);
     Ok(())
 }
 
-impl State for GameState {
-    
-    fn update(&mut self, ctx: &mut Context) -> tetra::Result {
+/// Radar overlay: for every rock that's off-screen, draw a small arrow
+/// blip at the screen edge pointing in its direction from the player.
+fn draw_radar_blips(
+    ctx: &mut Context,
+    player: &Actor,
+    rocks: &[Actor],
+    screen_width: f32,
+    screen_height: f32,
+) -> tetra::Result {
+    const EDGE_MARGIN: f32 = 20.0;
+    const BLIP_LENGTH: f32 = 10.0;
+    const BLIP_WIDTH: f32 = 6.0;
+
+    let center = Point2::new(screen_width / 2.0, screen_height / 2.0);
+
+    for rock in rocks {
+        let screen_pos = world_to_screen_coords(screen_width, screen_height, rock.pos);
+        let offscreen = screen_pos.x < 0.0
+            || screen_pos.x > screen_width
+            || screen_pos.y < 0.0
+            || screen_pos.y > screen_height;
+        if !offscreen {
+            continue;
+        }
+
+        // World Y points up, screen Y points down, so flip Y when
+        // carrying the direction from world space to screen space.
+        let to_rock = rock.pos - player.pos;
+        let dir = Vector2::new(to_rock.x, -to_rock.y).normalized();
+        // Push the blip out along `dir` until it meets the margin-inset screen rectangle.
+        let reach = ((screen_width / 2.0 - EDGE_MARGIN) / dir.x.abs())
+            .min((screen_height / 2.0 - EDGE_MARGIN) / dir.y.abs());
+        let blip_pos = center + dir * reach;
+        let side = Vector2::new(-dir.y, dir.x);
+
+        let tip = blip_pos + dir * BLIP_LENGTH;
+        let left = blip_pos + side * (BLIP_WIDTH / 2.0);
+        let right = blip_pos - side * (BLIP_WIDTH / 2.0);
+
+        let mesh = GeometryBuilder::new()
+            .polygon(ShapeStyle::Fill, &[tip, left, right])?
+            .build_mesh(ctx)?;
+        graphics::draw(ctx, &mesh, DrawParams::new().color(Color::rgb(1.0, 0.3, 0.3)));
+    }
+
+    Ok(())
+}
+
+impl GameState {
+
+    /// Advances the simulation by one frame. Returns `true` once the
+    /// player has died, so the caller can move to the game-over scene.
+    fn update(&mut self, ctx: &mut Context) -> tetra::Result<bool> {
         const DESIRED_FPS : u32 = 60;
         let seconds = 1.0 / (DESIRED_FPS as f32);
         // Update the player state based on the user input.
@@ -403,13 +597,22 @@ impl State for GameState {
             } else {
                 0.
             };
-        self.input.fire = 
+        self.input.fire =
             if input::is_key_down(ctx, Key::Space) {
                 true
             } else {
                 false
             };
-        player_handle_input(&mut self.player, &self.input, seconds);
+
+        if input::is_key_pressed(ctx, Key::Num1) {
+            self.active_system = ShipSystem::Engines;
+        } else if input::is_key_pressed(ctx, Key::Num2) {
+            self.active_system = ShipSystem::Weapons;
+        } else if input::is_key_pressed(ctx, Key::Num3) {
+            self.active_system = ShipSystem::Radar;
+        }
+
+        player_handle_input(&mut self.player, &self.input, seconds, self.active_system);
         self.player_shot_timeout -= seconds;
         if self.input.fire && self.player_shot_timeout < 0.0 {
             self.fire_player_shot(ctx);
@@ -417,6 +620,9 @@ impl State for GameState {
         if self.input.yaxis != 0.{
             //self.player
         }
+        if self.player.life > 0.0 {
+            self.player.life -= seconds;
+        }
 
         //Update the physics for all actors.
         // First the player...
@@ -444,15 +650,9 @@ impl State for GameState {
 
         self.check_for_level_respawn(ctx);
 
-        // Finally we check for our end state
-        // I wnat to have a nice death screen eventually,
-        // but for now we just quit
-        if self.player.life <= 0.0 {
-            println!("Game over!");
-            tetra::window::quit(ctx);
-        }
-
-        Ok(())
+        // Finally we check for our end state and let the caller
+        // switch scenes instead of quitting outright.
+        Ok(self.lives <= 0)
     }
 
     fn draw(&mut self, ctx: &mut Context) -> tetra::Result {
@@ -463,27 +663,35 @@ impl State for GameState {
             let coords = (self.screen_width, self.screen_height);
 
             let p = &self.player;
-            draw_actor(ctx, p, coords)?;
+            draw_actor(ctx, p, coords, self.player_alpha())?;
 
             for s in &self.shots {
-                draw_actor(ctx, s, coords)?;
+                draw_actor(ctx, s, coords, 1.0)?;
             }
 
             for r in &self.rocks {
-                draw_actor(ctx, r, coords)?;
+                draw_actor(ctx, r, coords, 1.0)?;
+            }
+
+            if self.active_system == ShipSystem::Radar {
+                draw_radar_blips(ctx, &self.player, &self.rocks, self.screen_width, self.screen_height)?;
             }
         }
 
         // and draw the GUI elements in the right places.
         let level_dest = Point2::new(10.0, 10.0);
         let score_dest = Point2::new(200.0, 10.0);
+        let lives_dest = Point2::new(400.0, 10.0);
 
         let level_str = format!("Level: {}", self.level);
         let score_str = format!("Score: {}", self.score);
+        let lives_str = format!("Lives: {}", self.lives);
         let level_display = graphics::Text::new(level_str, Font::default(), 32.0);
         let score_display = graphics::Text::new(score_str, Font::default(), 32.0);
+        let lives_display = graphics::Text::new(lives_str, Font::default(), 32.0);
         graphics::draw(ctx, &level_display, DrawParams::new().position(level_dest));
         graphics::draw(ctx, &score_display, DrawParams::new().position(score_dest));
+        graphics::draw(ctx, &lives_display, DrawParams::new().position(lives_dest));
 
         // And yield the timeline
         // This tells the OS that we're done using the CPU but it should
@@ -495,9 +703,83 @@ impl State for GameState {
     }
 }
 
+/// Which top-level scene is currently being driven. `GameState` only ever
+/// knows how to play a single round; switching rounds is `Game`'s job.
+enum Scene {
+    Playing,
+    GameOver { final_score: i32, level: i32 },
+}
+
+/// Owns the active scene and the `GameState` for whichever round is live,
+/// and dispatches `update`/`draw` to the right place.
+struct Game {
+    scene: Scene,
+    play: GameState,
+}
+
+impl Game {
+    fn new(ctx: &mut Context) -> tetra::Result<Game> {
+        Ok(Game {
+            scene: Scene::Playing,
+            play: GameState::new(ctx)?,
+        })
+    }
+
+    fn restart(&mut self, ctx: &mut Context) -> tetra::Result {
+        self.play = GameState::new(ctx)?;
+        self.scene = Scene::Playing;
+        Ok(())
+    }
+}
+
+fn draw_game_over(ctx: &mut Context, final_score: i32, level: i32) -> tetra::Result {
+    graphics::clear(ctx, Color::rgb(0.0, 0.0, 0.0));
+
+    let score_str = format!("Final score: {} (level {})", final_score, level);
+    let prompt_str = "Press Enter to restart, Esc to quit";
+
+    let score_display = graphics::Text::new(score_str, Font::default(), 32.0);
+    let prompt_display = graphics::Text::new(prompt_str, Font::default(), 24.0);
+    graphics::draw(ctx, &score_display, DrawParams::new().position(Point2::new(200.0, 250.0)));
+    graphics::draw(ctx, &prompt_display, DrawParams::new().position(Point2::new(150.0, 320.0)));
+
+    Ok(())
+}
+
+impl State for Game {
+    fn update(&mut self, ctx: &mut Context) -> tetra::Result {
+        match self.scene {
+            Scene::Playing => {
+                if self.play.update(ctx)? {
+                    self.scene = Scene::GameOver {
+                        final_score: self.play.score,
+                        level: self.play.level,
+                    };
+                }
+            }
+            Scene::GameOver { .. } => {
+                if input::is_key_pressed(ctx, Key::Enter) {
+                    self.restart(ctx)?;
+                } else if input::is_key_pressed(ctx, Key::Escape) {
+                    tetra::window::quit(ctx);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn draw(&mut self, ctx: &mut Context) -> tetra::Result {
+        match self.scene {
+            Scene::Playing => self.play.draw(ctx),
+            Scene::GameOver { final_score, level } => draw_game_over(ctx, final_score, level),
+        }
+    }
+}
+
 pub fn main() -> tetra::Result {
     ContextBuilder::new("Tetra Astroblasto", 800, 600)
         .quit_on_escape(true)
         .build()?
-        .run(GameState::new)
+        .run(Game::new)
 }
\ No newline at end of file